@@ -1,5 +1,6 @@
 use epub::doc::NavPoint;
 use std::{
+    collections::HashMap,
     fs::File,
     io::{BufReader, Read},
     num::NonZeroUsize,
@@ -7,50 +8,126 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use crate::reader;
+use crate::search::{self, SearchHit};
 use crate::utils;
 
 // TODO: Should I use tokio::{BufReader, File} instead?
 type Epub = epub::doc::EpubDoc<BufReader<File>>;
 
+/// File name of the search index database, stored alongside `metadata.db`
+/// in the Calibre library directory.
+const SEARCH_DB_FILE: &str = ".librari-search.db";
+
 // TODO: Can I use RwLock instead of Mutex?
 // TODO: Should I use tokio's Mutex?
 pub struct Library {
     base_path: PathBuf,
     db: Mutex<rusqlite::Connection>,
+    search_db: Mutex<rusqlite::Connection>,
     cache: Mutex<lru::LruCache<usize, Arc<Mutex<Epub>>>>,
 }
 
 impl Library {
     pub fn new(path: &Path, db: rusqlite::Connection) -> Self {
+        let search_db = rusqlite::Connection::open(path.join(SEARCH_DB_FILE))
+            .expect("Error opening search index database");
+        search_db
+            .execute_batch(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(
+                    book_id UNINDEXED,
+                    spine_path UNINDEXED,
+                    text
+                );",
+            )
+            .expect("Error creating search index table");
+
         Self {
             base_path: path.to_owned(),
             db: Mutex::new(db),
+            search_db: Mutex::new(search_db),
             cache: Mutex::new(lru::LruCache::new(NonZeroUsize::new(5).unwrap())),
         }
     }
 
-    /// List all books in the library
-    pub fn list_books(&self) -> Result<Vec<Book>, LibraryError> {
+    /// List a page of books, ordered by `sort_order`.
+    ///
+    /// Uses keyset pagination rather than `OFFSET`: `cursor` is the
+    /// `(sort_key, id)` of the last book on the previous page, so the query
+    /// can jump straight to the following row instead of re-scanning and
+    /// discarding everything before it. Returns the page together with the
+    /// cursor of its last book, to pass in for the next one. The cursor is
+    /// `None` only when the page is empty, so the last non-empty page still
+    /// comes with a cursor; a page shorter than `limit` is the last one.
+    pub fn list_books(
+        &self,
+        limit: u32,
+        cursor: Option<&Cursor>,
+        sort_order: SortOrder,
+    ) -> Result<(Vec<Book>, Option<Cursor>), LibraryError> {
+        let (column, dir) = sort_order.column_and_direction();
         let binding = self.db.lock().unwrap();
-        let mut stmt = binding
-            .prepare("SELECT id, title, author_sort, strftime('%Y', pubdate) as year, sort, has_cover FROM books")
-            .unwrap();
-        let books: Vec<Book> = stmt
-            .query_map((), |row| {
-                let id = row.get(0)?;
-                let sort_title: String = row.get(4)?;
-                Ok(Book {
-                    id,
-                    slug: format!("{}-{}", id, utils::slugify(&sort_title)),
-                    title: row.get(1)?,
-                    authors: row.get(2)?,
-                    year: row.get(3)?,
-                    has_cover: row.get(5)?,
-                })
-            })
-            .unwrap()
-            .map(|r| r.unwrap())
-            .collect();
+
+        let query = format!(
+            "SELECT id, title, author_sort, strftime('%Y', pubdate) as year, sort, has_cover, path, {column} as sort_key
+             FROM books
+             {where_clause}
+             ORDER BY {column} {dir}, id {dir}
+             LIMIT ?1",
+            where_clause = match (cursor, dir) {
+                (None, _) => "",
+                (Some(_), "ASC") => "WHERE (sort_key, id) > (?2, ?3)",
+                (Some(_), _) => "WHERE (sort_key, id) < (?2, ?3)",
+            },
+        );
+        let mut stmt = binding.prepare(&query).map_err(LibraryError::Sqlite)?;
+
+        let row_to_book = |row: &rusqlite::Row| -> rusqlite::Result<(Book, String)> {
+            let sort_key: String = row.get(7)?;
+            Ok((self.row_to_book(row)?, sort_key))
+        };
+
+        let rows: Vec<(Book, String)> = match cursor {
+            None => stmt
+                .query_map(rusqlite::params![limit], row_to_book)
+                .map_err(LibraryError::Sqlite)?
+                .collect::<Result<_, _>>()
+                .map_err(LibraryError::Sqlite)?,
+            Some(cursor) => stmt
+                .query_map(
+                    rusqlite::params![limit, cursor.key, cursor.id],
+                    row_to_book,
+                )
+                .map_err(LibraryError::Sqlite)?
+                .collect::<Result<_, _>>()
+                .map_err(LibraryError::Sqlite)?,
+        };
+
+        let next_cursor = rows.last().map(|(book, sort_key)| Cursor {
+            key: sort_key.clone(),
+            id: book.id,
+        });
+        let books = rows.into_iter().map(|(book, _)| book).collect();
+        Ok((books, next_cursor))
+    }
+
+    /// List every book in the library, ignoring pagination. Used for
+    /// operations that need the whole catalog, like building the search
+    /// index.
+    fn list_all_books(&self) -> Result<Vec<Book>, LibraryError> {
+        const PAGE_SIZE: u32 = 500;
+        let mut books = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (mut page, next_cursor) =
+                self.list_books(PAGE_SIZE, cursor.as_ref(), SortOrder::TitleAsc)?;
+            let got_full_page = page.len() as u32 == PAGE_SIZE;
+            books.append(&mut page);
+            match next_cursor {
+                Some(c) if got_full_page => cursor = Some(c),
+                _ => break,
+            }
+        }
         Ok(books)
     }
 
@@ -129,6 +206,62 @@ impl Library {
         Ok((info.title, index))
     }
 
+    /// Get the path to one of a book's downloadable formats (e.g. `epub`,
+    /// `pdf`, `mobi`, `azw3`, `cbz`), if the book has it.
+    pub fn get_format_path(&self, slug: &str, format: &str) -> Result<PathBuf, LibraryError> {
+        let info = self.get_book_info(slug)?;
+        info.formats()
+            .remove(format)
+            .ok_or(LibraryError::NotFound)
+    }
+
+    /// Get a chapter ready to render inline: its rewritten HTML (links and
+    /// resources pointed at the `/_/:slug/*path` route, library CSS
+    /// injected) plus its neighbours in the *spine* so the reader can offer
+    /// working previous/next buttons.
+    ///
+    /// NOTE: This uses the "spine", not the "toc" (see `get_book_index`) —
+    /// the spine is the book's actual reading order, which is what prev/next
+    /// navigation should follow.
+    pub fn get_chapter(&self, slug: &str, res_path: &str) -> Result<ChapterInfo, LibraryError> {
+        let info = self.get_book_info(slug)?;
+        let binding = self.get_epub_doc(&info)?;
+        let mut doc = binding.lock().unwrap();
+
+        let res_path_buf = PathBuf::from(res_path);
+        let target_id = doc
+            .resources
+            .iter()
+            .find(|(_, (path, _))| *path == res_path_buf)
+            .map(|(id, _)| id.clone())
+            .ok_or(LibraryError::NotFound)?;
+        let spine_idx = doc
+            .spine
+            .iter()
+            .position(|id| *id == target_id)
+            .ok_or(LibraryError::NotFound)?;
+
+        let spine_path = |idx: usize| -> Option<PathBuf> {
+            let id = doc.spine.get(idx)?;
+            doc.resources.get(id).map(|(path, _)| path.clone())
+        };
+        let prev_page = spine_idx.checked_sub(1).and_then(spine_path);
+        let next_page = spine_path(spine_idx + 1);
+
+        let content = doc
+            .get_resource_by_path(res_path)
+            .ok_or(LibraryError::NotFound)?;
+        let chapter_dir = res_path_buf.parent().unwrap_or(Path::new(""));
+        let content = reader::rewrite_chapter(&content, slug, chapter_dir);
+
+        Ok(ChapterInfo {
+            book_info: info,
+            content,
+            prev_page,
+            next_page,
+        })
+    }
+
     /// Get the book info from the database
     pub fn get_book_info(&self, slug: &str) -> Result<BookInfo, LibraryError> {
         let id = get_id(slug)?;
@@ -169,6 +302,292 @@ impl Library {
             .ok_or(LibraryError::NotFound)?;
         Epub::new(epub_path).map_err(LibraryError::Epub)
     }
+
+    /// (Re)build the full-text search index for every book in the library.
+    ///
+    /// Safe to call at any time: each book's rows are deleted before being
+    /// re-inserted, so re-indexing a changed book doesn't leave stale rows
+    /// behind.
+    pub fn build_search_index(&self) -> Result<(), LibraryError> {
+        for book in self.list_all_books()? {
+            self.index_book(&book)?;
+        }
+        Ok(())
+    }
+
+    /// Index (or re-index) a single book's spine into the search database.
+    fn index_book(&self, book: &Book) -> Result<(), LibraryError> {
+        let info = self.get_book_info(&book.slug)?;
+        let binding = self.get_epub_doc(&info)?;
+        let mut doc = binding.lock().unwrap();
+
+        let spine = doc.spine.clone();
+        let mut rows = Vec::new();
+        for res_id in &spine {
+            let Some((path, _mime)) = doc.resources.get(res_id).cloned() else {
+                continue;
+            };
+            let Some(content) = doc.get_resource_by_path(&path) else {
+                continue;
+            };
+            let text = search::extract_text(&content);
+            if !text.trim().is_empty() {
+                rows.push((path.to_string_lossy().into_owned(), text));
+            }
+        }
+        drop(doc);
+
+        let mut search_db = self.search_db.lock().unwrap();
+        let tx = search_db.transaction().map_err(LibraryError::Sqlite)?;
+        tx.execute(
+            "DELETE FROM search_index WHERE book_id = ?1",
+            rusqlite::params![book.id],
+        )
+        .map_err(LibraryError::Sqlite)?;
+        for (spine_path, text) in rows {
+            tx.execute(
+                "INSERT INTO search_index (book_id, spine_path, text) VALUES (?1, ?2, ?3)",
+                rusqlite::params![book.id, spine_path, text],
+            )
+            .map_err(LibraryError::Sqlite)?;
+        }
+        tx.commit().map_err(LibraryError::Sqlite)?;
+        Ok(())
+    }
+
+    /// Search the full-text index, returning the book and chapter each hit
+    /// was found in along with a snippet of surrounding text.
+    pub fn search(&self, query: &str) -> Result<Vec<SearchHit>, LibraryError> {
+        let search_db = self.search_db.lock().unwrap();
+        let mut stmt = search_db
+            .prepare(
+                "SELECT book_id, spine_path, snippet(search_index, 2, '<b>', '</b>', '…', 16)
+                 FROM search_index WHERE search_index MATCH ?1
+                 ORDER BY rank LIMIT 50",
+            )
+            .map_err(LibraryError::Sqlite)?;
+        let rows: Vec<(u64, String, String)> = stmt
+            .query_map(rusqlite::params![query], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .map_err(LibraryError::Sqlite)?
+            .collect::<Result<_, _>>()
+            .map_err(LibraryError::Sqlite)?;
+        drop(stmt);
+        drop(search_db);
+
+        let db = self.db.lock().unwrap();
+        let mut hits = Vec::with_capacity(rows.len());
+        for (book_id, spine_path, snippet) in rows {
+            let sort_title: String = db
+                .query_row("SELECT sort FROM books WHERE id = ?1", [book_id], |row| {
+                    row.get(0)
+                })
+                .map_err(LibraryError::Sqlite)?;
+            hits.push(SearchHit {
+                slug: format!("{}-{}", book_id, utils::slugify(&sort_title)),
+                spine_path,
+                snippet,
+            });
+        }
+        Ok(hits)
+    }
+
+    /// List all authors in the library.
+    pub fn list_authors(&self) -> Result<Vec<Author>, LibraryError> {
+        let binding = self.db.lock().unwrap();
+        let mut stmt = binding
+            .prepare("SELECT id, name FROM authors ORDER BY sort")
+            .map_err(LibraryError::Sqlite)?;
+        stmt.query_map((), |row| {
+            Ok(Author {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })
+        .map_err(LibraryError::Sqlite)?
+        .collect::<Result<_, _>>()
+        .map_err(LibraryError::Sqlite)
+    }
+
+    /// Get an author's name and every book of theirs, in title order.
+    pub fn books_by_author(&self, author_id: u64) -> Result<(String, Vec<Book>), LibraryError> {
+        let binding = self.db.lock().unwrap();
+        let name = binding
+            .query_row(
+                "SELECT name FROM authors WHERE id = ?1",
+                [author_id],
+                |row| row.get(0),
+            )
+            .map_err(LibraryError::Sqlite)?;
+
+        let mut stmt = binding
+            .prepare(
+                "SELECT b.id, b.title, b.author_sort, strftime('%Y', b.pubdate), b.sort, b.has_cover, b.path
+                 FROM books b
+                 JOIN books_authors_link bal ON bal.book = b.id
+                 WHERE bal.author = ?1
+                 ORDER BY b.sort",
+            )
+            .map_err(LibraryError::Sqlite)?;
+        let books = stmt
+            .query_map([author_id], |row| self.row_to_book(row))
+            .map_err(LibraryError::Sqlite)?
+            .collect::<Result<_, _>>()
+            .map_err(LibraryError::Sqlite)?;
+        Ok((name, books))
+    }
+
+    /// List all series in the library.
+    pub fn list_series(&self) -> Result<Vec<Series>, LibraryError> {
+        let binding = self.db.lock().unwrap();
+        let mut stmt = binding
+            .prepare("SELECT id, name FROM series ORDER BY sort")
+            .map_err(LibraryError::Sqlite)?;
+        stmt.query_map((), |row| {
+            Ok(Series {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })
+        .map_err(LibraryError::Sqlite)?
+        .collect::<Result<_, _>>()
+        .map_err(LibraryError::Sqlite)
+    }
+
+    /// Get a series' name and its books, in reading order (`series_index`).
+    pub fn books_by_series(
+        &self,
+        series_id: u64,
+    ) -> Result<(String, Vec<SeriesEntry>), LibraryError> {
+        let binding = self.db.lock().unwrap();
+        let name = binding
+            .query_row(
+                "SELECT name FROM series WHERE id = ?1",
+                [series_id],
+                |row| row.get(0),
+            )
+            .map_err(LibraryError::Sqlite)?;
+
+        let mut stmt = binding
+            .prepare(
+                "SELECT b.id, b.title, b.author_sort, strftime('%Y', b.pubdate), b.sort, b.has_cover, b.path, bsl.series_index
+                 FROM books b
+                 JOIN books_series_link bsl ON bsl.book = b.id
+                 WHERE bsl.series = ?1
+                 ORDER BY bsl.series_index",
+            )
+            .map_err(LibraryError::Sqlite)?;
+        let entries = stmt
+            .query_map([series_id], |row| {
+                Ok(SeriesEntry {
+                    book: self.row_to_book(row)?,
+                    series_index: row.get(7)?,
+                })
+            })
+            .map_err(LibraryError::Sqlite)?
+            .collect::<Result<_, _>>()
+            .map_err(LibraryError::Sqlite)?;
+        Ok((name, entries))
+    }
+
+    /// Build a `Book` from a row with the usual `id, title, author_sort,
+    /// year, sort, has_cover, path` column order, shared by every query that
+    /// lists books. `has_cover` itself isn't used: whether there's a cover
+    /// and what type it is are both determined by scanning the book's
+    /// directory, same as `formats`.
+    fn row_to_book(&self, row: &rusqlite::Row) -> rusqlite::Result<Book> {
+        let id = row.get(0)?;
+        let sort_title: String = row.get(4)?;
+        let path: String = row.get(6)?;
+        let dir = self.base_path.join(path);
+        Ok(Book {
+            id,
+            slug: format!("{}-{}", id, utils::slugify(&sort_title)),
+            title: row.get(1)?,
+            authors: row.get(2)?,
+            year: row.get(3)?,
+            cover_type: cover_content_type(&dir),
+            formats: scan_formats(&dir),
+        })
+    }
+}
+
+/// How a page of books from `Library::list_books` should be ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    TitleAsc,
+    AuthorAsc,
+    PubdateDesc,
+    /// Most recently added first (Calibre's `timestamp` column).
+    Added,
+}
+
+impl SortOrder {
+    /// The `books` column to sort by, and the direction to sort it in.
+    /// Both come from this fixed set of variants, never from user input, so
+    /// it's safe to interpolate them directly into SQL.
+    fn column_and_direction(&self) -> (&'static str, &'static str) {
+        match self {
+            SortOrder::TitleAsc => ("sort", "ASC"),
+            SortOrder::AuthorAsc => ("author_sort", "ASC"),
+            SortOrder::PubdateDesc => ("pubdate", "DESC"),
+            SortOrder::Added => ("timestamp", "DESC"),
+        }
+    }
+
+    /// Parse the `?sort=` query param used by the web and OPDS routes.
+    pub fn parse(s: &str) -> Option<SortOrder> {
+        match s {
+            "title" => Some(SortOrder::TitleAsc),
+            "author" => Some(SortOrder::AuthorAsc),
+            "pubdate" => Some(SortOrder::PubdateDesc),
+            "added" => Some(SortOrder::Added),
+            _ => None,
+        }
+    }
+
+    /// The `?sort=` query param value for this order.
+    pub fn as_query_str(&self) -> &'static str {
+        match self {
+            SortOrder::TitleAsc => "title",
+            SortOrder::AuthorAsc => "author",
+            SortOrder::PubdateDesc => "pubdate",
+            SortOrder::Added => "added",
+        }
+    }
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::TitleAsc
+    }
+}
+
+/// A keyset pagination cursor: the sort key and id of the last book on the
+/// previous page.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    key: String,
+    id: u64,
+}
+
+impl Cursor {
+    /// Encode as a single opaque string suitable for a `?cursor=` query
+    /// param. `id` is numeric, so splitting from the right is unambiguous
+    /// even if `key` itself contains the separator.
+    pub fn encode(&self) -> String {
+        format!("{}~{}", self.key, self.id)
+    }
+
+    /// Decode a cursor previously produced by `encode`.
+    pub fn decode(s: &str) -> Option<Cursor> {
+        let (key, id) = s.rsplit_once('~')?;
+        Some(Cursor {
+            key: key.to_owned(),
+            id: id.parse().ok()?,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -178,7 +597,13 @@ pub struct Book {
     pub title: String,
     pub authors: String,
     pub year: String,
-    pub has_cover: bool,
+
+    /// Content type of the cover image (`image/jpeg` or `image/png`), or
+    /// `None` if the book has no cover file.
+    pub cover_type: Option<&'static str>,
+
+    /// Downloadable formats (`epub`, `pdf`, `mobi`, ...) keyed by extension.
+    pub formats: HashMap<String, PathBuf>,
 }
 
 pub struct BookInfo {
@@ -192,12 +617,98 @@ pub struct BookInfo {
     pub title: String,
 }
 
+impl BookInfo {
+    /// Downloadable formats (`epub`, `pdf`, `mobi`, ...) keyed by extension,
+    /// found by scanning the book's directory in the Calibre library.
+    pub fn formats(&self) -> HashMap<String, PathBuf> {
+        scan_formats(&self.path)
+    }
+}
+
+pub struct Author {
+    pub id: u64,
+    pub name: String,
+}
+
+pub struct Series {
+    pub id: u64,
+    pub name: String,
+}
+
+/// One book's place within a series, as returned by `Library::books_by_series`.
+pub struct SeriesEntry {
+    pub book: Book,
+    pub series_index: f64,
+}
+
+/// Guess the content type for one of the formats returned by
+/// `BookInfo::formats`, falling back to a generic binary type for anything
+/// unrecognized.
+pub fn format_content_type(ext: &str) -> &'static str {
+    match ext {
+        "epub" => "application/epub+zip",
+        "pdf" => "application/pdf",
+        "mobi" => "application/x-mobipocket-ebook",
+        "azw3" => "application/vnd.amazon.ebook",
+        "cbz" => "application/vnd.comicbook+zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Guess the content type of a book's cover image, mirroring the
+/// `cover.jpg`/`cover.png` check `Library::get_cover` uses to find the file.
+fn cover_content_type(dir: &Path) -> Option<&'static str> {
+    if dir.join("cover.jpg").is_file() {
+        Some("image/jpeg")
+    } else if dir.join("cover.png").is_file() {
+        Some("image/png")
+    } else {
+        None
+    }
+}
+
+/// Scan a book's directory in the Calibre library for downloadable formats,
+/// keyed by lowercased extension (`epub`, `pdf`, `mobi`, `azw3`, `cbz`, ...).
+fn scan_formats(dir: &Path) -> HashMap<String, PathBuf> {
+    let Ok(entries) = dir.read_dir() else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        // Calibre keeps the cover and the metadata file next to the book
+        // files; those aren't formats of the book.
+        .filter(|p| {
+            !matches!(
+                p.file_name().and_then(|n| n.to_str()),
+                Some("cover.jpg" | "cover.png" | "metadata.opf")
+            )
+        })
+        .filter_map(|p| {
+            let ext = p.extension()?.to_str()?.to_lowercase();
+            Some((ext, p))
+        })
+        .collect()
+}
+
 pub struct IndexItem {
     pub label: String,
     pub path: PathBuf,
     pub level: u32,
 }
 
+pub struct ChapterInfo {
+    pub book_info: BookInfo,
+
+    /// The chapter's HTML, with resource links rewritten to the
+    /// `/_/:slug/*path` route and the library's CSS injected.
+    pub content: String,
+
+    /// The previous/next resource in the EPUB *spine*, if any.
+    pub prev_page: Option<PathBuf>,
+    pub next_page: Option<PathBuf>,
+}
+
 pub enum LibraryError {
     NotFound,
     InvalidId(String),
@@ -2,12 +2,13 @@ use std::collections::HashMap;
 use std::env;
 use std::sync::Arc;
 
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::{Html, IntoResponse, Response};
 use axum::{extract::State, routing::get};
 use lazy_static::lazy_static;
 use md5::Digest;
+use serde::Deserialize;
 
 struct StaticFile {
     content_type: &'static str,
@@ -53,9 +54,17 @@ lazy_static! {
 }
 
 mod library;
+mod opds;
+mod reader;
+mod search;
 mod templates;
 mod utils;
 
+use library::{Cursor, SortOrder};
+
+/// Default page size for `list_books`, used whenever `?limit=` is absent.
+const DEFAULT_PAGE_SIZE: u32 = 50;
+
 struct AppState {
     library: library::Library,
 }
@@ -79,13 +88,29 @@ async fn main() {
         library: library::Library::new(dir, db),
     });
 
+    // TODO: Watch the library for changes instead of only indexing at startup.
+    if shared_state.library.build_search_index().is_err() {
+        println!("Warning: failed to build search index");
+    }
+
     let router = axum::Router::new()
         .route("/", get(handle_home))
+        .route("/recent", get(handle_recent))
+        .route("/search", get(handle_search))
+        .route("/authors", get(handle_authors))
+        .route("/author/:id", get(handle_author))
+        .route("/series", get(handle_series_list))
+        .route("/series/:id", get(handle_series))
         .route("/:slug", get(handle_book_index))
         .route("/:slug/cover", get(handle_book_cover))
+        .route("/:slug/download/:format", get(handle_book_download))
         .route("/:slug/*page", get(handle_book_page))
         .route("/_/:slug/*path", get(handle_book_resource))
         .route("/assets/*path", get(handle_assets))
+        .route("/opds", get(handle_opds_root))
+        .route("/opds/recent", get(handle_opds_recent))
+        .route("/opds/authors", get(handle_opds_authors))
+        .route("/opds/search", get(handle_opds_search))
         .with_state(shared_state);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:8007")
@@ -96,12 +121,51 @@ async fn main() {
     axum::serve(listener, router).await.unwrap();
 }
 
-async fn handle_home(State(state): State<Arc<AppState>>) -> Response {
+#[derive(Deserialize)]
+struct BookListParams {
+    sort: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+async fn handle_home(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<BookListParams>,
+) -> Response {
+    render_book_list(&state, params, SortOrder::TitleAsc).await
+}
+
+async fn handle_recent(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<BookListParams>,
+) -> Response {
+    render_book_list(&state, params, SortOrder::PubdateDesc).await
+}
+
+async fn render_book_list(
+    state: &Arc<AppState>,
+    params: BookListParams,
+    default_sort: SortOrder,
+) -> Response {
+    let sort_order = params
+        .sort
+        .as_deref()
+        .and_then(SortOrder::parse)
+        .unwrap_or(default_sort);
+    let cursor = params.cursor.as_deref().and_then(Cursor::decode);
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
     let library = &state.library;
-    let Ok(books) = library.list_books() else {
+    let Ok((books, next_cursor)) = library.list_books(limit, cursor.as_ref(), sort_order) else {
         return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing books").into_response();
     };
-    Html(templates::render_home(&books)).into_response()
+    let next_cursor = next_cursor.as_ref().map(Cursor::encode);
+    Html(templates::render_home(
+        &books,
+        sort_order,
+        next_cursor.as_deref(),
+    ))
+    .into_response()
 }
 
 async fn handle_book_index(
@@ -112,7 +176,18 @@ async fn handle_book_index(
     let Ok((title, book_index)) = library.get_book_index(&slug) else {
         return (StatusCode::NOT_FOUND, "Book not found").into_response();
     };
-    Html(templates::render_book_index(title, &book_index, &slug)).into_response()
+    let Ok(info) = library.get_book_info(&slug) else {
+        return (StatusCode::NOT_FOUND, "Book not found").into_response();
+    };
+    let mut formats: Vec<String> = info.formats().into_keys().collect();
+    formats.sort();
+    Html(templates::render_book_index(
+        title,
+        &book_index,
+        &slug,
+        &formats,
+    ))
+    .into_response()
 }
 
 async fn handle_book_cover(
@@ -134,16 +209,46 @@ async fn handle_book_cover(
         .into_response()
 }
 
+async fn handle_book_download(
+    Path((slug, format)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let library = &state.library;
+    let Ok(info) = library.get_book_info(&slug) else {
+        return (StatusCode::NOT_FOUND, "Book not found").into_response();
+    };
+    let Ok(path) = library.get_format_path(&slug, &format) else {
+        return (StatusCode::NOT_FOUND, "Format not found").into_response();
+    };
+    let Ok(content) = std::fs::read(&path) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error reading file").into_response();
+    };
+
+    let filename = format!("{}.{}", utils::slugify(&info.title), format);
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, library::format_content_type(&format)),
+            (
+                header::CONTENT_DISPOSITION,
+                &format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        content,
+    )
+        .into_response()
+}
+
 async fn handle_book_page(
     Path((slug, res_path)): Path<(String, String)>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
     let library = &state.library;
-    let Ok(book_info) = library.get_book_info(&slug) else {
+    let Ok(chapter_info) = library.get_chapter(&slug, &res_path) else {
         return (StatusCode::NOT_FOUND, "Book not found").into_response();
     };
 
-    Html(templates::render_page(&book_info.title, &slug, &res_path)).into_response()
+    Html(templates::render_page(&chapter_info, &slug, &res_path)).into_response()
 }
 
 async fn handle_book_resource(
@@ -165,6 +270,145 @@ async fn handle_book_resource(
         .into_response()
 }
 
+#[derive(Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+}
+
+async fn handle_search(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    let query = params.q.unwrap_or_default();
+    if query.is_empty() {
+        return Html(templates::render_search(&query, &Vec::new())).into_response();
+    }
+
+    let library = &state.library;
+    let Ok(hits) = library.search(&query) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error searching library").into_response();
+    };
+    Html(templates::render_search(&query, &hits)).into_response()
+}
+
+async fn handle_authors(State(state): State<Arc<AppState>>) -> Response {
+    let library = &state.library;
+    let Ok(authors) = library.list_authors() else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing authors").into_response();
+    };
+    Html(templates::render_authors(&authors)).into_response()
+}
+
+async fn handle_author(Path(id): Path<u64>, State(state): State<Arc<AppState>>) -> Response {
+    let library = &state.library;
+    let Ok((name, books)) = library.books_by_author(id) else {
+        return (StatusCode::NOT_FOUND, "Author not found").into_response();
+    };
+    Html(templates::render_author(&name, &books)).into_response()
+}
+
+async fn handle_series_list(State(state): State<Arc<AppState>>) -> Response {
+    let library = &state.library;
+    let Ok(series) = library.list_series() else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing series").into_response();
+    };
+    Html(templates::render_series_list(&series)).into_response()
+}
+
+async fn handle_series(Path(id): Path<u64>, State(state): State<Arc<AppState>>) -> Response {
+    let library = &state.library;
+    let Ok((name, entries)) = library.books_by_series(id) else {
+        return (StatusCode::NOT_FOUND, "Series not found").into_response();
+    };
+    Html(templates::render_series(&name, &entries)).into_response()
+}
+
+async fn handle_opds_root() -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, opds::NAVIGATION_TYPE)],
+        opds::render_navigation_feed(),
+    )
+        .into_response()
+}
+
+/// OPDS feeds don't paginate (yet), so just grab a generously large page.
+const OPDS_PAGE_SIZE: u32 = 500;
+
+async fn handle_opds_recent(State(state): State<Arc<AppState>>) -> Response {
+    let library = &state.library;
+    let Ok((books, _)) = library.list_books(OPDS_PAGE_SIZE, None, SortOrder::Added) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing books").into_response();
+    };
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, opds::ACQUISITION_TYPE)],
+        opds::render_acquisition_feed("recent", "Recently added", &books),
+    )
+        .into_response()
+}
+
+async fn handle_opds_authors(State(state): State<Arc<AppState>>) -> Response {
+    let library = &state.library;
+    let Ok(authors) = library.list_authors() else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing authors").into_response();
+    };
+    // One flat acquisition feed, books grouped by author in author order.
+    // TODO: Split into per-author subsection feeds once OPDS routing grows
+    // past the four routes this catalog started with.
+    let mut books = Vec::new();
+    for author in &authors {
+        let Ok((_, mut author_books)) = library.books_by_author(author.id) else {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing books").into_response();
+        };
+        books.append(&mut author_books);
+    }
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, opds::ACQUISITION_TYPE)],
+        opds::render_acquisition_feed("authors", "Books by author", &books),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct OpdsSearchParams {
+    q: Option<String>,
+}
+
+async fn handle_opds_search(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<OpdsSearchParams>,
+) -> Response {
+    let Some(query) = params.q.filter(|q| !q.is_empty()) else {
+        return (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/opensearchdescription+xml")],
+            opds::render_opensearch_description(),
+        )
+            .into_response();
+    };
+
+    let library = &state.library;
+    let Ok((books, _)) = library.list_books(OPDS_PAGE_SIZE, None, SortOrder::TitleAsc) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error listing books").into_response();
+    };
+    // TODO: This matches on title/author metadata, not book content, so it
+    // stays separate from Library::search (the full-text index over chapter
+    // text used by the /search web route).
+    let query = query.to_lowercase();
+    let matches: Vec<_> = books
+        .into_iter()
+        .filter(|b| b.title.to_lowercase().contains(&query) || b.authors.to_lowercase().contains(&query))
+        .collect();
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, opds::ACQUISITION_TYPE)],
+        opds::render_acquisition_feed("search", "Search results", &matches),
+    )
+        .into_response()
+}
+
 async fn handle_assets(Path(asset_path): Path<String>, headers: HeaderMap) -> Response {
     let Some(file) = ASSETS.get(&*asset_path) else {
         return (StatusCode::NOT_FOUND, "Asset not found").into_response();
@@ -0,0 +1,154 @@
+//! OPDS (Open Publication Distribution System) catalog feeds.
+//!
+//! These let e-reader apps (KOReader, Thorium, Moon+ Reader, ...) browse and
+//! download books directly, without a web browser. The root is a
+//! *navigation* feed linking to the sub-feeds below; the sub-feeds are
+//! *acquisition* feeds whose entries can be downloaded.
+//!
+//! See https://specs.opds.io/opds-1.2 for the spec this loosely follows.
+
+use crate::library::{format_content_type, Book};
+use crate::utils::escape_xml;
+
+pub const NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+pub const ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+/// Render the root OPDS navigation feed, linking to the catalog's sub-feeds.
+pub fn render_navigation_feed() -> String {
+    let entries = [
+        ("All books", "/opds/recent", "Most recently added books"),
+        ("Authors", "/opds/authors", "Browse books by author"),
+    ]
+    .iter()
+    .map(|(title, href, content)| {
+        format!(
+            r#"  <entry>
+    <title>{title}</title>
+    <id>urn:librari:opds:{href}</id>
+    <updated>{updated}</updated>
+    <content type="text">{content}</content>
+    <link rel="subsection" href="{href}" type="{ACQUISITION_TYPE}"/>
+  </entry>
+"#,
+            title = escape_xml(title),
+            href = href,
+            content = escape_xml(content),
+            updated = FIXED_UPDATED,
+        )
+    })
+    .collect::<String>();
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:opds="http://opds-spec.org/2010/catalog">
+  <id>urn:librari:opds:root</id>
+  <title>My books</title>
+  <updated>{updated}</updated>
+  <link rel="self" href="/opds" type="{NAVIGATION_TYPE}"/>
+  <link rel="start" href="/opds" type="{NAVIGATION_TYPE}"/>
+  <link rel="search" href="/opds/search" type="{OPENSEARCH_TYPE}"/>
+{entries}</feed>
+"#,
+        updated = FIXED_UPDATED,
+    )
+}
+
+/// Render an acquisition feed (a list of downloadable books) as Atom.
+pub fn render_acquisition_feed(id: &str, title: &str, books: &[Book]) -> String {
+    let entries = books.iter().map(render_entry).collect::<String>();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:opds="http://opds-spec.org/2010/catalog">
+  <id>urn:librari:opds:{id}</id>
+  <title>{title}</title>
+  <updated>{updated}</updated>
+  <link rel="self" href="/opds/{id}" type="{ACQUISITION_TYPE}"/>
+  <link rel="start" href="/opds" type="{NAVIGATION_TYPE}"/>
+{entries}</feed>
+"#,
+        id = id,
+        title = escape_xml(title),
+        updated = FIXED_UPDATED,
+    )
+}
+
+/// Render the OpenSearch description document advertised by the root feed's
+/// `rel="search"` link, so clients know how to query `/opds/search`.
+pub fn render_opensearch_description() -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <ShortName>My books</ShortName>
+  <Description>Search this library by title or author</Description>
+  <InputEncoding>UTF-8</InputEncoding>
+  <OutputEncoding>UTF-8</OutputEncoding>
+  <Url type="{ACQUISITION_TYPE}" template="/opds/search?q={{searchTerms}}"/>
+</OpenSearchDescription>
+"#
+    )
+}
+
+fn render_entry(book: &Book) -> String {
+    let cover_link = if let Some(cover_type) = book.cover_type {
+        format!(
+            r#"    <link rel="http://opds-spec.org/image" href="/{slug}/cover" type="{cover_type}"/>
+    <link rel="http://opds-spec.org/image/thumbnail" href="/{slug}/cover" type="{cover_type}"/>
+"#,
+            slug = book.slug,
+            cover_type = cover_type,
+        )
+    } else {
+        String::new()
+    };
+
+    let acquisition_link = match preferred_format(book) {
+        Some(ext) => format!(
+            r#"    <link rel="http://opds-spec.org/acquisition" href="/{slug}/download/{ext}" type="{content_type}"/>
+"#,
+            slug = book.slug,
+            ext = ext,
+            content_type = format_content_type(ext),
+        ),
+        // No known format on disk to offer a download for; still list the
+        // book so its metadata (and cover) show up in the feed.
+        None => String::new(),
+    };
+
+    format!(
+        r#"  <entry>
+    <title>{title}</title>
+    <id>urn:librari:book:{id}</id>
+    <author><name>{authors}</name></author>
+    <updated>{updated}</updated>
+{cover_link}{acquisition_link}  </entry>
+"#,
+        title = escape_xml(&book.title),
+        id = book.id,
+        authors = escape_xml(&book.authors),
+        updated = entry_updated(&book.year),
+        cover_link = cover_link,
+        acquisition_link = acquisition_link,
+    )
+}
+
+/// Pick which of a book's formats to offer as the OPDS acquisition link:
+/// epub if there is one (the format every e-reader app speaks), otherwise
+/// whatever else is available.
+fn preferred_format(book: &Book) -> Option<&str> {
+    if book.formats.contains_key("epub") {
+        return Some("epub");
+    }
+    book.formats.keys().next().map(String::as_str)
+}
+
+/// Atom requires a full RFC 3339 timestamp; `Book` only carries the
+/// publication year, so pin everything else to the start of that year.
+fn entry_updated(year: &str) -> String {
+    format!("{}-01-01T00:00:00Z", year)
+}
+
+// Placeholder timestamp for feed-level `<updated>`. Calibre's metadata.db
+// doesn't track a "catalog last changed" time, so this is good enough for
+// e-reader clients that mostly care about entry ordering, not this value.
+const FIXED_UPDATED: &str = "1970-01-01T00:00:00Z";
@@ -0,0 +1,110 @@
+//! Turns a raw EPUB chapter (XHTML, with links and resources that only make
+//! sense inside the zip file) into something a browser can render directly:
+//! every `href`/`src` that points at another resource in the book gets
+//! rewritten to go through the `/_/:slug/*path` resource route, and the
+//! library's own `page.css` gets injected so the chapter picks up our
+//! reading styles instead of (or in addition to) its own.
+
+use std::io::Write;
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::utils;
+
+/// Rewrite one chapter's XHTML so it can be served and rendered as-is.
+///
+/// `chapter_dir` is the directory (inside the EPUB) the chapter resource
+/// lives in, used to resolve the relative links found in its markup.
+pub fn rewrite_chapter(xhtml: &[u8], slug: &str, chapter_dir: &Path) -> String {
+    let mut reader = Reader::from_reader(xhtml);
+    reader.config_mut().trim_text(false);
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    let mut injected_css = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => {
+                let is_head = e.name().as_ref() == b"head";
+                let _ = writer.write_event(Event::Start(rewrite_links(&e, slug, chapter_dir)));
+                if is_head {
+                    inject_page_css(&mut writer);
+                    injected_css = true;
+                }
+            }
+            Ok(Event::Empty(e)) => {
+                let _ = writer.write_event(Event::Empty(rewrite_links(&e, slug, chapter_dir)));
+            }
+            Ok(Event::Eof) => break,
+            Err(_) => break,
+            Ok(event) => {
+                let _ = writer.write_event(event);
+            }
+        }
+        buf.clear();
+    }
+
+    let mut out = String::from_utf8(writer.into_inner()).unwrap_or_default();
+    if !injected_css {
+        // No `<head>` to hook into (some chapters are body-only fragments);
+        // fall back to putting our stylesheet link first.
+        out.insert_str(0, PAGE_CSS_LINK);
+    }
+    out
+}
+
+const PAGE_CSS_LINK: &str = "<link rel=\"stylesheet\" href=\"/assets/page.css\">";
+
+fn inject_page_css(writer: &mut Writer<Vec<u8>>) {
+    let _ = writer.get_mut().write_all(PAGE_CSS_LINK.as_bytes());
+}
+
+/// Rewrite the `href`/`src` attributes of one element, if they point at
+/// another resource inside the book.
+fn rewrite_links(start: &BytesStart, slug: &str, chapter_dir: &Path) -> BytesStart<'static> {
+    let mut elem = BytesStart::new(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+    for attr in start.attributes().flatten() {
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        let value = attr.unescape_value().unwrap_or_default().into_owned();
+        let rewritten = if key == "href" || key == "src" {
+            resolve_link(chapter_dir, &value, slug).unwrap_or(value)
+        } else {
+            value
+        };
+        elem.push_attribute((key.as_str(), rewritten.as_str()));
+    }
+    elem.into_owned()
+}
+
+/// Resolve a link found in a chapter's markup to a `/_/:slug/*path` URL, or
+/// `None` if it's something that shouldn't be rewritten (an absolute URL, a
+/// `mailto:` link, or a same-page anchor).
+fn resolve_link(chapter_dir: &Path, href: &str, slug: &str) -> Option<String> {
+    if href.starts_with('#')
+        || href.contains("://")
+        || href.starts_with("mailto:")
+        || href.starts_with("data:")
+    {
+        return None;
+    }
+
+    let (path, fragment) = href.split_once('#').unwrap_or((href, ""));
+    if path.is_empty() {
+        return None;
+    }
+
+    let resolved = utils::normalize_path(&chapter_dir.join(path));
+    let fragment = if fragment.is_empty() {
+        String::new()
+    } else {
+        format!("#{fragment}")
+    };
+    Some(format!(
+        "/_/{}/{}{}",
+        slug,
+        resolved.to_string_lossy(),
+        fragment
+    ))
+}
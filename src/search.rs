@@ -0,0 +1,99 @@
+//! Full-text extraction for the search index.
+//!
+//! EPUB chapters are XHTML, so to index their visible text we stream through
+//! the markup rather than parsing a full DOM: most of a chapter is prose we
+//! want verbatim, and the handful of elements that aren't (`<script>`,
+//! `<style>`, `<nav>`, `<svg>`, `<iframe>`) are rare enough that a simple
+//! "ignoring" flag is cheaper than building a tree just to throw it away.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+const IGNORED_TAGS: [&[u8]; 5] = [b"script", b"style", b"nav", b"svg", b"iframe"];
+
+/// Resolve the HTML named entities that quick-xml's default unescaping
+/// doesn't know about (it only handles the five XML entities).  `&nbsp;` is
+/// the one that matters here: left as-is it's invisible in the index, and
+/// dropped entirely it glues the words on either side of it together.
+fn resolve_html_entity(name: &str) -> Option<&str> {
+    match name {
+        "nbsp" => Some("\u{00a0}"),
+        _ => None,
+    }
+}
+
+/// One full-text search result: the book it was found in, the spine path of
+/// the matching chapter, and a snippet of the surrounding text.
+pub struct SearchHit {
+    pub slug: String,
+    pub spine_path: String,
+    pub snippet: String,
+}
+
+/// Extract the visible text of one XHTML chapter resource.
+///
+/// Text inside `<script>`, `<style>`, `<nav>`, `<svg>`, and `<iframe>` is
+/// skipped. `<h1>`-`<h6>` headings are kept inline with the rest of the
+/// text (there's no separate "chapter title" column in the index) so a
+/// search can still match on them.
+pub fn extract_text(xhtml: &[u8]) -> String {
+    let mut reader = Reader::from_reader(xhtml);
+    reader.config_mut().trim_text(false);
+    // Report self-closing tags as a start/end pair, so a self-closing ignored
+    // element (e.g. `<svg/>`) opens and closes `ignoring` like any other.
+    reader.config_mut().expand_empty_elements = true;
+
+    let mut buf = Vec::new();
+    let mut out = String::new();
+    let mut ignoring = 0u32;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => {
+                if is_ignored(&e) {
+                    ignoring += 1;
+                }
+            }
+            Ok(Event::End(e)) => {
+                if IGNORED_TAGS.contains(&e.name().as_ref()) && ignoring > 0 {
+                    ignoring -= 1;
+                }
+            }
+            // Character data needs entity unescaping (`&amp;`, `&nbsp;`, ...),
+            // not just charset decoding, or escaped markup ends up verbatim
+            // in the index.
+            Ok(Event::Text(e)) if ignoring == 0 => {
+                if let Ok(text) = e.unescape_with(resolve_html_entity) {
+                    append_text(&mut out, &text);
+                }
+            }
+            // CDATA sections are treated the same as regular character data,
+            // so embedded markup-as-text doesn't get lost. Its content isn't
+            // escaped, so plain decoding (no unescaping) is correct here.
+            Ok(Event::CData(e)) if ignoring == 0 => {
+                if let Ok(text) = e.decode() {
+                    append_text(&mut out, &text);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    out
+}
+
+fn is_ignored(start: &BytesStart) -> bool {
+    IGNORED_TAGS.contains(&start.name().as_ref())
+}
+
+fn append_text(out: &mut String, text: &str) {
+    if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    // `&nbsp;` decodes to U+00A0; store it as a plain space so the indexed
+    // text only separates words with ordinary spaces.
+    out.extend(text.chars().map(|c| if c == '\u{00a0}' { ' ' } else { c }));
+}
@@ -1,37 +1,41 @@
-use crate::library::{Book, ChapterInfo, IndexItem};
+use crate::library::{Author, Book, ChapterInfo, IndexItem, Series, SeriesEntry, SortOrder};
+use crate::search::SearchHit;
 use askama::Template;
 
-pub fn render_home(books: &Vec<Book>) -> String {
+pub fn render_home(books: &Vec<Book>, sort_order: SortOrder, next_cursor: Option<&str>) -> String {
     let home = HomeTemplate {
         title: "My books",
         books,
+        sort: sort_order.as_query_str(),
+        next_cursor,
     };
     home.render().unwrap()
 }
 
-pub fn render_book_index(title: String, book_index: &Vec<IndexItem>, book_slug: &str) -> String {
+pub fn render_book_index(
+    title: String,
+    book_index: &Vec<IndexItem>,
+    book_slug: &str,
+    formats: &Vec<String>,
+) -> String {
     (BookIndexTemplate {
         title: &title,
         items: book_index,
         book_slug,
+        formats,
     })
     .render()
     .unwrap()
 }
 
 pub fn render_page(chapter_info: &ChapterInfo, book_slug: &str, res_path: &str) -> String {
-    let prev_page = chapter_info
-        .prev_page
-        .as_ref()
-        .and_then(|p| Some(p.to_str().unwrap()));
-    let next_page = chapter_info
-        .next_page
-        .as_ref()
-        .and_then(|p| Some(p.to_str().unwrap()));
+    let prev_page = chapter_info.prev_page.as_ref().and_then(|p| p.to_str());
+    let next_page = chapter_info.next_page.as_ref().and_then(|p| p.to_str());
     (PageTemplate {
         title: &chapter_info.book_info.title,
         slug: book_slug,
         res_path,
+        content: &chapter_info.content,
         prev_page,
         next_page,
     })
@@ -39,11 +43,43 @@ pub fn render_page(chapter_info: &ChapterInfo, book_slug: &str, res_path: &str)
     .unwrap()
 }
 
+pub fn render_search(query: &str, hits: &Vec<SearchHit>) -> String {
+    (SearchTemplate { query, hits }).render().unwrap()
+}
+
+pub fn render_authors(authors: &Vec<Author>) -> String {
+    (AuthorsTemplate {
+        title: "Authors",
+        authors,
+    })
+    .render()
+    .unwrap()
+}
+
+pub fn render_author(name: &str, books: &Vec<Book>) -> String {
+    (AuthorTemplate { name, books }).render().unwrap()
+}
+
+pub fn render_series_list(series: &Vec<Series>) -> String {
+    (SeriesListTemplate {
+        title: "Series",
+        series,
+    })
+    .render()
+    .unwrap()
+}
+
+pub fn render_series(name: &str, entries: &Vec<SeriesEntry>) -> String {
+    (SeriesTemplate { name, entries }).render().unwrap()
+}
+
 #[derive(Template)]
 #[template(path = "home.html")]
 struct HomeTemplate<'a> {
     title: &'a str,
     books: &'a Vec<Book>,
+    sort: &'a str,
+    next_cursor: Option<&'a str>,
 }
 
 #[derive(Template)]
@@ -52,6 +88,7 @@ struct BookIndexTemplate<'a> {
     title: &'a str,
     items: &'a Vec<IndexItem>,
     book_slug: &'a str,
+    formats: &'a Vec<String>,
 }
 
 #[derive(Template)]
@@ -60,6 +97,42 @@ struct PageTemplate<'a> {
     title: &'a str,
     slug: &'a str,
     res_path: &'a str,
+    content: &'a str,
     prev_page: Option<&'a str>,
     next_page: Option<&'a str>,
 }
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate<'a> {
+    query: &'a str,
+    hits: &'a Vec<SearchHit>,
+}
+
+#[derive(Template)]
+#[template(path = "authors.html")]
+struct AuthorsTemplate<'a> {
+    title: &'a str,
+    authors: &'a Vec<Author>,
+}
+
+#[derive(Template)]
+#[template(path = "author.html")]
+struct AuthorTemplate<'a> {
+    name: &'a str,
+    books: &'a Vec<Book>,
+}
+
+#[derive(Template)]
+#[template(path = "series_list.html")]
+struct SeriesListTemplate<'a> {
+    title: &'a str,
+    series: &'a Vec<Series>,
+}
+
+#[derive(Template)]
+#[template(path = "series.html")]
+struct SeriesTemplate<'a> {
+    name: &'a str,
+    entries: &'a Vec<SeriesEntry>,
+}
@@ -1,4 +1,5 @@
 use std::num::ParseIntError;
+use std::path::{Component, Path, PathBuf};
 
 pub fn slugify(input: &str) -> String {
     input
@@ -21,3 +22,36 @@ pub fn extract_id(input: &str) -> Result<usize, ParseIntError> {
         .collect::<String>()
         .parse()
 }
+
+/// Collapse `.` and `..` components so a relative link from inside an EPUB
+/// (e.g. `../images/cover.jpg`) resolves to a clean path, the way a browser
+/// would resolve it against the current document's URL.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir => {}
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Escape the characters XML requires escaped in text content and attribute
+/// values (`&`, `<`, `>`, `"`, `'`).
+pub fn escape_xml(input: &str) -> String {
+    input
+        .chars()
+        .flat_map(|c| match c {
+            '&' => "&amp;".chars().collect::<Vec<_>>(),
+            '<' => "&lt;".chars().collect(),
+            '>' => "&gt;".chars().collect(),
+            '"' => "&quot;".chars().collect(),
+            '\'' => "&apos;".chars().collect(),
+            c => vec![c],
+        })
+        .collect()
+}